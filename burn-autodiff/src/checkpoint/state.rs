@@ -1,10 +1,43 @@
-use std::{any::Any, collections::HashMap};
+use std::{any::Any, collections::HashMap, collections::HashSet};
+
+use arena::Arena;
 
 use crate::graph::NodeID;
 
 /// In order to accept arbitrary node output in the same hashmap, we need to upcast them to any.
 pub(crate) type StateContent = Box<dyn Any + Send + Sync>;
 
+/// A logical tick of [BackwardStates]'s internal clock, used to measure how long a
+/// [Computed](State::Computed) state has been sitting unused for eviction purposes.
+pub(crate) type Clock = u64;
+
+/// Ticks a node must go unaccessed before it's eligible for eviction, to avoid evicting
+/// freshly computed states ahead of genuinely stale ones.
+const EVICTION_GRACE_TICKS: Clock = 2;
+
+#[derive(Debug, Clone)]
+/// Memory-accounting metadata attached to a [Computed](State::Computed) state for the
+/// budget-driven eviction policy.
+pub(crate) struct ComputedMeta {
+    /// Resident size in bytes, as reported by the caller of [BackwardStates::save].
+    pub(crate) bytes: usize,
+    /// Estimated cost of recomputing this node from its parents.
+    pub(crate) recompute_cost: u64,
+    /// Logical clock value at the last access, used to derive staleness.
+    pub(crate) last_access: Clock,
+    /// The parents this state was computed from, needed to drive recomputation if evicted.
+    pub(crate) parents: Vec<NodeID>,
+}
+
+impl ComputedMeta {
+    /// The DTR-style eviction heuristic: cheap-to-recompute, large, stale states score low
+    /// and are evicted first.
+    fn heuristic(&self, clock: Clock) -> f64 {
+        let staleness = clock.saturating_sub(self.last_access) as f64 + f64::EPSILON;
+        self.recompute_cost as f64 / (self.bytes.max(1) as f64 * staleness)
+    }
+}
+
 #[derive(Debug)]
 /// The state contained at one node. Encapsulates the node output if precomputed,
 /// or clearly asks that it needs to be recomputed from the parents.
@@ -17,6 +50,7 @@ pub(crate) enum State {
     Computed {
         state_content: StateContent,
         n_required: usize,
+        meta: ComputedMeta,
     },
 }
 
@@ -30,6 +64,7 @@ impl State {
             State::Computed {
                 state_content,
                 n_required: _,
+                meta: _,
             } => state_content,
         }
     }
@@ -43,10 +78,19 @@ impl State {
             State::Computed {
                 state_content,
                 n_required: _,
+                meta: _,
             } => state_content,
         }
     }
 
+    /// Returns the metadata needed to drive eviction, if this state is checkpointed.
+    pub(crate) fn computed_meta(&self) -> Option<&ComputedMeta> {
+        match self {
+            State::Recompute { .. } => None,
+            State::Computed { meta, .. } => Some(meta),
+        }
+    }
+
     /// Returns the number of time the state is required
     pub(crate) fn n_required(&self) -> usize {
         match self {
@@ -54,6 +98,7 @@ impl State {
             State::Computed {
                 state_content: _,
                 n_required,
+                meta: _,
             } => *n_required,
         }
     }
@@ -64,40 +109,210 @@ impl State {
             State::Computed {
                 state_content: _,
                 n_required,
+                meta: _,
             } => *n_required += 1,
         }
     }
 
+    /// Refreshes the last-access tick of a [Computed](State::Computed) state; a no-op for
+    /// [Recompute](State::Recompute) states, which carry no staleness information.
+    pub(crate) fn refresh_last_access(&mut self, clock: Clock) {
+        if let State::Computed { meta, .. } = self {
+            meta.last_access = clock;
+        }
+    }
+
     pub(crate) fn merge(&mut self, other: Self) {
         match other {
             State::Recompute { n_required: n } => match self {
                 State::Recompute { n_required } => *n_required += n,
-                State::Computed {
-                    state_content,
-                    n_required,
-                } => panic!("Not supposed to happen"),
+                State::Computed { .. } => panic!("Not supposed to happen"),
             },
-            State::Computed {
-                state_content,
-                n_required: n,
-            } => match self {
-                State::Recompute { n_required } => panic!("Not supposed to happen"),
-                State::Computed {
-                    state_content,
-                    n_required,
-                } => *n_required += n,
+            State::Computed { n_required: n, .. } => match self {
+                State::Recompute { .. } => panic!("Not supposed to happen"),
+                State::Computed { n_required, .. } => *n_required += n,
             },
         }
     }
 }
 
+/// A bump-allocated, chunk-growing arena used by [BackwardStates] to store [State]s, so
+/// indices stay valid for the arena's whole lifetime and storage is freed in bulk.
+mod arena {
+    use std::{mem::MaybeUninit, ptr::NonNull};
+
+    const INITIAL_CHUNK_CAPACITY: usize = 64;
+
+    /// A single fixed-capacity block of arena storage, allocated once at its final capacity
+    /// and never reallocated.
+    struct Chunk<T> {
+        ptr: NonNull<[MaybeUninit<T>]>,
+        len: usize,
+    }
+
+    impl<T> Chunk<T> {
+        fn with_capacity(capacity: usize) -> Self {
+            let boxed: Box<[MaybeUninit<T>]> = (0..capacity).map(|_| MaybeUninit::uninit()).collect();
+            let ptr = NonNull::new(Box::into_raw(boxed)).expect("boxed allocation is never null");
+            Self { ptr, len: 0 }
+        }
+
+        fn capacity(&self) -> usize {
+            unsafe { self.ptr.as_ref().len() }
+        }
+
+        fn push(&mut self, value: T) -> usize {
+            debug_assert!(self.len < self.capacity());
+            let index = self.len;
+            unsafe {
+                (*self.ptr.as_ptr())[index].write(value);
+            }
+            self.len += 1;
+            index
+        }
+
+        fn get(&self, index: usize) -> &T {
+            debug_assert!(index < self.len);
+            unsafe { (*self.ptr.as_ptr())[index].assume_init_ref() }
+        }
+
+        fn get_mut(&mut self, index: usize) -> &mut T {
+            debug_assert!(index < self.len);
+            unsafe { (*self.ptr.as_ptr())[index].assume_init_mut() }
+        }
+
+        /// Moves every initialized value out of the chunk into `out`, without running their
+        /// destructors, and marks the chunk as empty so its own [Drop] impl doesn't then
+        /// double-drop them.
+        fn drain_into(&mut self, out: &mut Vec<T>) {
+            out.reserve(self.len);
+            for i in 0..self.len {
+                unsafe {
+                    out.push((*self.ptr.as_ptr())[i].assume_init_read());
+                }
+            }
+            self.len = 0;
+        }
+    }
+
+    impl<T> Drop for Chunk<T> {
+        fn drop(&mut self) {
+            unsafe {
+                for slot in &mut (*self.ptr.as_ptr())[..self.len] {
+                    slot.assume_init_drop();
+                }
+                drop(Box::from_raw(self.ptr.as_ptr()));
+            }
+        }
+    }
+
+    // SAFETY: a `Chunk<T>` owns its `T`s exactly like a `Box<[T]>` would.
+    unsafe impl<T: Send> Send for Chunk<T> {}
+    unsafe impl<T: Sync> Sync for Chunk<T> {}
+
+    /// A growable arena of `T` slots, backed by doubling-capacity [Chunk]s.
+    #[derive(Default)]
+    pub(super) struct Arena<T> {
+        chunks: Vec<Chunk<T>>,
+    }
+
+    impl<T> Arena<T> {
+        /// Pushes `value` into the arena and returns the dense index it was stored at. That
+        /// index is stable for the lifetime of the arena.
+        pub(super) fn push(&mut self, value: T) -> usize {
+            if self.chunks.last().map_or(true, |c| c.len == c.capacity()) {
+                let next_capacity = self
+                    .chunks
+                    .last()
+                    .map_or(INITIAL_CHUNK_CAPACITY, |c| c.capacity() * 2);
+                self.chunks.push(Chunk::with_capacity(next_capacity));
+            }
+
+            let chunk_index = self.chunks.len() - 1;
+            let offset: usize = self.chunks[..chunk_index].iter().map(|c| c.capacity()).sum();
+            offset + self.chunks[chunk_index].push(value)
+        }
+
+        fn locate(&self, index: usize) -> (usize, usize) {
+            let mut remaining = index;
+            for (chunk_index, chunk) in self.chunks.iter().enumerate() {
+                if remaining < chunk.capacity() {
+                    return (chunk_index, remaining);
+                }
+                remaining -= chunk.capacity();
+            }
+            unreachable!("arena index out of bounds")
+        }
+
+        pub(super) fn get(&self, index: usize) -> &T {
+            let (chunk_index, offset) = self.locate(index);
+            self.chunks[chunk_index].get(offset)
+        }
+
+        pub(super) fn get_mut(&mut self, index: usize) -> &mut T {
+            let (chunk_index, offset) = self.locate(index);
+            self.chunks[chunk_index].get_mut(offset)
+        }
+
+        /// Total number of values ever pushed, including orphaned slots.
+        pub(super) fn len(&self) -> usize {
+            self.chunks.iter().map(|c| c.len).sum()
+        }
+
+        /// Consumes the arena, returning every stored value in push order. Bulk-frees all
+        /// chunks as it goes rather than dropping one slot at a time.
+        pub(super) fn into_values(mut self) -> Vec<T> {
+            let mut out = Vec::new();
+            for chunk in self.chunks.iter_mut() {
+                chunk.drain_into(&mut out);
+            }
+            out
+        }
+    }
+
+    impl<T> std::fmt::Debug for Arena<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("Arena")
+                .field("len", &self.chunks.iter().map(|c| c.len).sum::<usize>())
+                .finish()
+        }
+    }
+}
+
 #[derive(new, Default, Debug)]
-/// Links [NodeID]s to their current [State]
+/// Links [NodeID]s to their current [State], backed by a chunked [Arena].
 pub struct BackwardStates {
-    map: HashMap<NodeID, State>,
+    /// Maps each node to the dense index of its [State] in `arena`.
+    #[new(default)]
+    indices: HashMap<NodeID, usize>,
+    #[new(default)]
+    arena: Arena<State>,
+    /// Optional cap, in bytes, on the total size of resident [Computed](State::Computed)
+    /// states. When set, [insert_state] evicts the least valuable states until
+    /// [resident_bytes] is back under budget or no evictable state remains.
+    #[new(default)]
+    memory_budget: Option<usize>,
+    /// Running total of bytes resident across all [Computed](State::Computed) states.
+    #[new(default)]
+    resident_bytes: usize,
+    /// Logical clock, incremented on every [get_state] access, used to compute staleness.
+    #[new(default)]
+    clock: Clock,
+    /// Nodes currently mid-access; excluded from eviction so a node can't be evicted out from
+    /// under the access that is in the middle of reading it.
+    #[new(default)]
+    in_progress: HashSet<NodeID>,
 }
 
 impl BackwardStates {
+    /// Caps the total resident size of [Computed](State::Computed) states at `bytes`,
+    /// enabling DTR-style eviction of checkpointed activations back to
+    /// [Recompute](State::Recompute) whenever a new one would exceed the budget.
+    pub(crate) fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
     /// Returns the output in the [State] of the given [NodeID],
     /// and decrements the number of times this state is required.
     /// This function always gives ownership of the output, but will clone it if needed for further uses.
@@ -105,57 +320,80 @@ impl BackwardStates {
     where
         T: Clone + Send + Sync + 'static,
     {
-        // Fetch the state and decrement its number of required
-        let state = self.map.remove(node_id).unwrap();
-        let remaining_n_required = state.n_required() - 1;
-
-        // Downcast the state to whatever it is supposed to be
-        // If still needed after giving ownership, we copy it back to the hashmap
-        if remaining_n_required > 0 {
-            println!("reinserting node {:?}", node_id);
-            let new_stored_state = match state {
-                State::Recompute { n_required: _ } => State::Recompute {
-                    n_required: remaining_n_required,
-                },
-                State::Computed {
-                    state_content,
-                    n_required: _,
-                } => State::Computed {
-                    state_content,
-                    n_required: remaining_n_required,
-                },
-            };
+        self.clock += 1;
+        self.in_progress.insert(node_id.clone());
 
-            let downcasted = new_stored_state
-                .to_state_content()
-                .downcast_ref::<T>()
-                .unwrap()
-                .clone();
+        let index = *self.indices.get(node_id).unwrap();
+        let remaining_n_required = self.arena.get(index).n_required() - 1;
 
-            self.insert_state(node_id.clone(), new_stored_state);
+        let downcasted = if remaining_n_required > 0 {
+            let state = self.arena.get_mut(index);
+            match state {
+                State::Recompute { n_required } => *n_required = remaining_n_required,
+                State::Computed { n_required, .. } => *n_required = remaining_n_required,
+            }
+            state.refresh_last_access(self.clock);
 
-            downcasted
+            state.to_state_content().downcast_ref::<T>().unwrap().clone()
         } else {
-            println!("NOT reinserting node {:?}", node_id);
-            println!("{:?}", self.map.len());
+            // Fully consumed: drop the `indices` mapping so `get_state_ref`/`len` observe the
+            // node as gone, matching the baseline HashMap-backed behavior. The now-orphaned
+            // arena slot holds a no-op placeholder and is reclaimed in bulk at teardown.
+            let state = std::mem::replace(self.arena.get_mut(index), State::Recompute { n_required: 0 });
+            self.indices.remove(node_id);
+            if let Some(meta) = state.computed_meta() {
+                self.resident_bytes = self.resident_bytes.saturating_sub(meta.bytes);
+            }
             let downcasted = state.into_state_content().downcast::<T>().unwrap();
             *downcasted
-        }
+        };
+
+        self.in_progress.remove(node_id);
+
+        downcasted
     }
 
     /// Returns a reference to the [State] of the given node
     /// Useful when we need [State] information without needing the underlying tensor
     pub(crate) fn get_state_ref(&self, node_id: &NodeID) -> Option<&State> {
-        self.map.get(node_id)
+        self.indices.get(node_id).map(|&index| self.arena.get(index))
     }
 
     /// Associates a [State] to its [NodeID]
     pub(crate) fn insert_state(&mut self, node_id: NodeID, state: State) {
-        self.map.insert(node_id, state);
+        let new_bytes = state.computed_meta().map(|meta| meta.bytes).unwrap_or(0);
+
+        match self.indices.get(&node_id).copied() {
+            Some(index) => {
+                let old_bytes = self
+                    .arena
+                    .get(index)
+                    .computed_meta()
+                    .map(|meta| meta.bytes)
+                    .unwrap_or(0);
+                *self.arena.get_mut(index) = state;
+                self.resident_bytes = (self.resident_bytes + new_bytes).saturating_sub(old_bytes);
+            }
+            None => {
+                let index = self.arena.push(state);
+                self.indices.insert(node_id, index);
+                self.resident_bytes += new_bytes;
+            }
+        }
+
+        self.evict_to_budget();
     }
 
-    pub(crate) fn save<T>(&mut self, node_id: NodeID, saved_output: T)
-    where
+    /// Checkpoints `saved_output` for `node_id`. `bytes` must be the actual resident size of
+    /// `saved_output`, since `size_of::<T>()` only measures the wrapper's stack footprint.
+    pub(crate) fn save<T>(
+        &mut self,
+        node_id: NodeID,
+        saved_output: T,
+        bytes: usize,
+        recompute_cost: u64,
+        parents: Vec<NodeID>,
+    ) where
         T: Clone + Send + Sync + 'static,
     {
         let n_required = self.get_state_ref(&node_id).unwrap().n_required();
@@ -164,42 +402,238 @@ impl BackwardStates {
             State::Computed {
                 state_content: Box::new(saved_output),
                 n_required,
+                meta: ComputedMeta {
+                    bytes,
+                    recompute_cost,
+                    last_access: self.clock,
+                    parents,
+                },
             },
         );
     }
 
+    /// Evicts [Computed](State::Computed) states with the lowest heuristic score until
+    /// [resident_bytes] is back under [memory_budget] or no evictable candidate remains.
+    fn evict_to_budget(&mut self) {
+        let Some(budget) = self.memory_budget else {
+            return;
+        };
+
+        while self.resident_bytes > budget {
+            let victim = self
+                .indices
+                .iter()
+                .filter(|(node_id, &index)| {
+                    let state = self.arena.get(index);
+                    !self.in_progress.contains(*node_id)
+                        && state.n_required() > 1
+                        && state.computed_meta().is_some_and(|meta| {
+                            self.clock.saturating_sub(meta.last_access) >= EVICTION_GRACE_TICKS
+                        })
+                })
+                .filter_map(|(node_id, &index)| {
+                    self.arena
+                        .get(index)
+                        .computed_meta()
+                        .map(|meta| (node_id.clone(), meta.heuristic(self.clock)))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(node_id, _)| node_id);
+
+            let Some(node_id) = victim else {
+                break;
+            };
+            self.evict(&node_id);
+        }
+    }
+
+    /// Drops the stored [StateContent] of `node_id`, replacing it with [Recompute](State::Recompute).
+    fn evict(&mut self, node_id: &NodeID) {
+        let Some(&index) = self.indices.get(node_id) else {
+            return;
+        };
+
+        let n_required = self.arena.get(index).n_required();
+        let state = std::mem::replace(self.arena.get_mut(index), State::Recompute { n_required });
+        if let Some(meta) = state.computed_meta() {
+            self.resident_bytes = self.resident_bytes.saturating_sub(meta.bytes);
+        }
+    }
+
     pub(crate) fn extend(&mut self, other: Self) {
-        // println!("extending");
-        // println!("..");
-        // println!("{:?}", self.map.keys());
-        // println!("{:?}", self.map.values());
-        // println!("..");
-        // println!("{:?}", other.map.keys());
-        // println!("{:?}", other.map.values());
-        // println!("..");
-        for (node_id, state) in other.map.into_iter() {
-            // println!("{:?}", node_id);
-            match self.map.remove(&node_id) {
-                Some(mut s) => {
-                    // println!("!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
-                    s.merge(state);
-                    self.map.insert(node_id, s);
-                }
+        let BackwardStates { indices, arena, .. } = other;
+
+        let mut index_to_node = vec![None; arena.len()];
+        for (node_id, index) in indices {
+            index_to_node[index] = Some(node_id);
+        }
+
+        for (node_id, state) in index_to_node.into_iter().zip(arena.into_values()) {
+            // Slots orphaned by a fully consumed `get_state` have no node id and are dropped.
+            let Some(node_id) = node_id else {
+                continue;
+            };
+            match self.indices.get(&node_id).copied() {
+                Some(index) => self.arena.get_mut(index).merge(state),
                 None => {
-                    self.map.insert(node_id, state);
+                    let index = self.arena.push(state);
+                    self.indices.insert(node_id, index);
                 }
             }
         }
-        // println!("-> {:?}", self.map.keys());
-        // println!("-> {:?}", self.map.values());
-        // println!("\n\n")
+
+        self.resident_bytes = self
+            .indices
+            .values()
+            .filter_map(|&index| self.arena.get(index).computed_meta())
+            .map(|meta| meta.bytes)
+            .sum();
     }
 
     pub(crate) fn len(&self) -> usize {
-        self.map.len()
+        self.indices.len()
     }
 
     pub(crate) fn get_mut(&mut self, node_id: &NodeID) -> Option<&mut State> {
-        self.map.get_mut(node_id)
+        self.indices.get(node_id).map(|&index| self.arena.get_mut(index))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn computed(bytes: usize, recompute_cost: u64, last_access: Clock, n_required: usize) -> State {
+        State::Computed {
+            state_content: Box::new(0u32),
+            n_required,
+            meta: ComputedMeta {
+                bytes,
+                recompute_cost,
+                last_access,
+                parents: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn evicts_lowest_heuristic_candidate_first() {
+        let mut states = BackwardStates::new().with_memory_budget(150);
+        states.clock = 10;
+
+        let cheap_large_stale = NodeID::new();
+        let costly_small = NodeID::new();
+
+        // h = cost / (bytes * staleness): cheap to recompute and large -> low h -> evicted.
+        states.insert_state(cheap_large_stale.clone(), computed(100, 1, 0, 2));
+        // expensive to recompute and small -> high h -> kept, even though it tips the total
+        // over budget and forces eviction to run.
+        states.insert_state(costly_small.clone(), computed(60, 1_000_000, 0, 2));
+
+        assert!(matches!(
+            states.get_state_ref(&cheap_large_stale),
+            Some(State::Recompute { .. })
+        ));
+        assert!(matches!(
+            states.get_state_ref(&costly_small),
+            Some(State::Computed { .. })
+        ));
+    }
+
+    #[test]
+    fn respects_grace_period_for_freshly_computed_states() {
+        let mut states = BackwardStates::new().with_memory_budget(50);
+        states.clock = 1;
+
+        let fresh = NodeID::new();
+        // last_access == clock: within the grace period, so it must survive even though it
+        // alone already exceeds the budget.
+        states.insert_state(fresh.clone(), computed(100, 1, 1, 2));
+
+        assert!(matches!(
+            states.get_state_ref(&fresh),
+            Some(State::Computed { .. })
+        ));
+    }
+
+    #[test]
+    fn never_evicts_a_state_about_to_be_fully_consumed() {
+        let mut states = BackwardStates::new().with_memory_budget(50);
+        states.clock = 10;
+
+        let about_to_finish = NodeID::new();
+        // n_required == 1: one access away from being removed entirely, must not be evicted.
+        states.insert_state(about_to_finish.clone(), computed(100, 1, 0, 1));
+
+        assert!(matches!(
+            states.get_state_ref(&about_to_finish),
+            Some(State::Computed { .. })
+        ));
+    }
+
+    #[test]
+    fn arena_grows_across_chunks_without_losing_earlier_values() {
+        let mut states = BackwardStates::new();
+        let mut ids = Vec::new();
+        for _ in 0..200 {
+            let node_id = NodeID::new();
+            states.insert_state(node_id.clone(), State::Recompute { n_required: 1 });
+            ids.push(node_id);
+        }
+
+        assert_eq!(states.len(), 200);
+        for node_id in &ids {
+            assert_eq!(states.get_state_ref(node_id).unwrap().n_required(), 1);
+        }
+    }
+
+    #[test]
+    fn get_state_removes_the_mapping_once_fully_consumed() {
+        let mut states = BackwardStates::new();
+        let node_id = NodeID::new();
+        states.insert_state(node_id.clone(), State::Recompute { n_required: 1 });
+        states.save(node_id.clone(), 7u32, 4, 1, Vec::new());
+
+        assert_eq!(states.len(), 1);
+        let value: u32 = states.get_state(&node_id);
+        assert_eq!(value, 7);
+        assert!(states.get_state_ref(&node_id).is_none());
+        assert_eq!(states.len(), 0);
+    }
+
+    #[test]
+    fn extend_after_partial_consumption_does_not_panic() {
+        let mut states = BackwardStates::new();
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let node_id = NodeID::new();
+            states.insert_state(node_id.clone(), State::Recompute { n_required: 1 });
+            ids.push(node_id);
+        }
+
+        // Fully consume the first node: its `indices` entry is removed, but the arena slot
+        // stays, so `indices.len()` (2) now trails the arena's total length (3).
+        states.save(ids[0].clone(), 0u32, 1, 1, Vec::new());
+        let _: u32 = states.get_state(&ids[0]);
+
+        let mut merged = BackwardStates::new();
+        merged.extend(states);
+
+        assert_eq!(merged.get_state_ref(&ids[1]).unwrap().n_required(), 1);
+        assert_eq!(merged.get_state_ref(&ids[2]).unwrap().n_required(), 1);
+    }
+
+    #[test]
+    fn extend_merges_required_counts_for_shared_nodes() {
+        let mut a = BackwardStates::new();
+        let mut b = BackwardStates::new();
+        let shared = NodeID::new();
+
+        a.insert_state(shared.clone(), State::Recompute { n_required: 1 });
+        b.insert_state(shared.clone(), State::Recompute { n_required: 2 });
+
+        a.extend(b);
+
+        assert_eq!(a.get_state_ref(&shared).unwrap().n_required(), 3);
+    }
+}
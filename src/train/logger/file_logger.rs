@@ -0,0 +1,178 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    marker::PhantomData,
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::data::dataloader::Progress;
+
+use super::logger::{LogItem, Logger};
+
+/// On-disk record format written by [FileLogger].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileLoggerFormat {
+    /// One JSON object per line.
+    Json,
+    /// Comma-separated values, with a header row written from the first record.
+    Csv,
+}
+
+enum FileLoggerWriter {
+    Json(BufWriter<File>),
+    Csv {
+        writer: csv::Writer<File>,
+        header_written: bool,
+    },
+}
+
+/// A [Logger] that appends one record per [log](Logger::log) call to a file, as either JSON
+/// lines or CSV.
+pub struct FileLogger<T> {
+    writer: FileLoggerWriter,
+    timestamp_format: Option<String>,
+    _item: PhantomData<T>,
+}
+
+impl<T> FileLogger<T> {
+    /// Creates a new file logger writing to `path` in the given `format`, truncating any
+    /// existing file at that path.
+    pub fn new(path: impl AsRef<Path>, format: FileLoggerFormat) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+
+        let writer = match format {
+            FileLoggerFormat::Json => FileLoggerWriter::Json(BufWriter::new(file)),
+            FileLoggerFormat::Csv => FileLoggerWriter::Csv {
+                writer: csv::Writer::from_writer(file),
+                header_written: false,
+            },
+        };
+
+        Ok(Self {
+            writer,
+            timestamp_format: None,
+            _item: PhantomData,
+        })
+    }
+
+    /// Prepends a wall-clock timestamp to every record, rendered with the given
+    /// `strftime`-style format string (e.g. `"%Y-%m-%dT%H:%M:%S"`).
+    pub fn with_timestamp_format(mut self, format: impl Into<String>) -> Self {
+        self.timestamp_format = Some(format.into());
+        self
+    }
+}
+
+/// Flattens `value` into `(key, value)` pairs: its top-level fields if it serializes to an
+/// object (`csv`'s `Serializer` doesn't support `#[serde(flatten)]`, so this flattens by hand),
+/// or a single `(fallback_key, value)` pair if it serializes to a scalar instead.
+fn flatten_to_columns<V: Serialize>(value: &V, fallback_key: &str) -> Vec<(String, String)> {
+    let render = |value: serde_json::Value| match value {
+        serde_json::Value::String(s) => s,
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    };
+
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::Object(map)) => {
+            map.into_iter().map(|(key, value)| (key, render(value))).collect()
+        }
+        Ok(scalar) => vec![(fallback_key.to_string(), render(scalar))],
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Flattens `value` into a JSON [Map]: its top-level fields if it serializes to an object, or
+/// a single `(fallback_key, value)` entry if it serializes to a scalar instead.
+fn flatten_to_json<V: Serialize>(
+    value: &V,
+    fallback_key: &str,
+) -> serde_json::Map<String, serde_json::Value> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::Object(map)) => map,
+        Ok(scalar) => {
+            let mut map = serde_json::Map::new();
+            map.insert(fallback_key.to_string(), scalar);
+            map
+        }
+        Err(_) => serde_json::Map::new(),
+    }
+}
+
+impl<T: Serialize + Send> Logger<T> for FileLogger<T> {
+    fn log(&mut self, item: LogItem<T>) {
+        let timestamp = self
+            .timestamp_format
+            .as_deref()
+            .map(|format| chrono::Local::now().format(format).to_string());
+
+        match &mut self.writer {
+            FileLoggerWriter::Json(writer) => {
+                let mut record = serde_json::Map::new();
+                if let Some(timestamp) = timestamp {
+                    record.insert("timestamp".to_string(), timestamp.into());
+                }
+                record.insert("epoch".to_string(), item.epoch.into());
+                record.insert("epoch_total".to_string(), item.epoch_total.into());
+                record.insert("iteration".to_string(), item.iteration.into());
+                record.extend(flatten_to_json(&item.progress, "progress"));
+                record.extend(flatten_to_json(&item.item, "value"));
+
+                // Built as a single `Value` before writing, so a record never gets
+                // partially written into the buffer.
+                if serde_json::to_writer(&mut *writer, &serde_json::Value::Object(record)).is_ok()
+                {
+                    let _ = writeln!(writer);
+                }
+            }
+            FileLoggerWriter::Csv {
+                writer,
+                header_written,
+            } => {
+                let progress_columns = flatten_to_columns(&item.progress, "progress");
+                let metric_columns = flatten_to_columns(&item.item, "value");
+
+                if !*header_written {
+                    let mut header: Vec<&str> = Vec::new();
+                    if timestamp.is_some() {
+                        header.push("timestamp");
+                    }
+                    header.extend(["epoch", "epoch_total", "iteration"]);
+                    header.extend(progress_columns.iter().map(|(key, _)| key.as_str()));
+                    header.extend(metric_columns.iter().map(|(key, _)| key.as_str()));
+                    let _ = writer.write_record(&header);
+                    *header_written = true;
+                }
+
+                let mut row: Vec<String> = Vec::new();
+                if let Some(timestamp) = timestamp {
+                    row.push(timestamp);
+                }
+                row.push(item.epoch.map(|v| v.to_string()).unwrap_or_default());
+                row.push(item.epoch_total.map(|v| v.to_string()).unwrap_or_default());
+                row.push(item.iteration.map(|v| v.to_string()).unwrap_or_default());
+                row.extend(progress_columns.into_iter().map(|(_, value)| value));
+                row.extend(metric_columns.into_iter().map(|(_, value)| value));
+
+                let _ = writer.write_record(&row);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        match &mut self.writer {
+            FileLoggerWriter::Json(writer) => {
+                let _ = writer.flush();
+            }
+            FileLoggerWriter::Csv { writer, .. } => {
+                let _ = writer.flush();
+            }
+        }
+    }
+}
@@ -0,0 +1,7 @@
+mod file_logger;
+mod logger;
+mod tracing_logger;
+
+pub use file_logger::{FileLogger, FileLoggerFormat};
+pub use logger::{LogItem, Logger};
+pub use tracing_logger::TracingLogger;
@@ -0,0 +1,51 @@
+use std::fmt::Display;
+use std::marker::PhantomData;
+
+use tracing::{span, Level};
+
+use super::logger::{LogItem, Logger};
+
+/// A [Logger] that emits each [log](Logger::log) call through `tracing` as an event inside an
+/// `epoch` span, which nests an `iteration` span. Both spans are created anew for every call
+/// and carry that item's epoch and iteration as fields; calls from the same epoch do not
+/// share one span.
+pub struct TracingLogger<T> {
+    _item: PhantomData<T>,
+}
+
+impl<T> Default for TracingLogger<T> {
+    fn default() -> Self {
+        Self { _item: PhantomData }
+    }
+}
+
+impl<T> TracingLogger<T> {
+    /// Creates a new tracing logger.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T: Display + Send> Logger<T> for TracingLogger<T> {
+    fn log(&mut self, item: LogItem<T>) {
+        let epoch_span = span!(
+            Level::INFO,
+            "epoch",
+            epoch = ?item.epoch,
+            epoch_total = ?item.epoch_total,
+        );
+        let _epoch_guard = epoch_span.enter();
+
+        let iteration_span = span!(Level::INFO, "iteration", iteration = ?item.iteration);
+        let _iteration_guard = iteration_span.enter();
+
+        tracing::event!(
+            Level::INFO,
+            progress = ?item.progress,
+            iteration = ?item.iteration,
+            value = %item.item,
+        );
+    }
+
+    fn clear(&mut self) {}
+}